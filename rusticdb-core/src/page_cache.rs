@@ -1,45 +1,102 @@
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
+use std::sync::Arc;
+use crate::allocator::Allocator;
 use crate::pager::{Pager, PAGE_SIZE};
 type PageData = Rc<RefCell<[u8; PAGE_SIZE]>>;
 
+/// A fixed-byte-budget LRU cache of pages layered over a `Pager`.
+///
+/// `pager` is an `Arc<Pager>` rather than an owned `Pager` so a cache and
+/// an `Allocator` (see `Allocator::pager`) can share one underlying file
+/// and `new_page` can hand back a page id that's immediately usable
+/// through this same cache. When sharing a `Pager` with an `Allocator`
+/// this way, never call `get_page`/`mark_dirty` directly with a page id
+/// for which `Allocator::is_reserved_page` returns `true` — those pages
+/// belong to the allocator's own metadata and are never produced by
+/// `new_page`.
 pub struct PageCache {
-    pager: Pager,
+    pager: Arc<Pager>,
     cache: HashMap<usize, PageData>,
     dirty_flag: HashMap<usize, bool>,
     lru: VecDeque<usize>,
-    capacity: usize,
+    capacity_bytes: usize,
+    hits: usize,
+    misses: usize,
 }
 
 impl PageCache {
-    pub fn new(pager: Pager, capacity: usize) -> Self {
+    pub fn new(pager: Arc<Pager>, capacity_bytes: usize) -> Self {
         Self {
             pager,
             cache: HashMap::new(),
             dirty_flag: HashMap::new(),
             lru: VecDeque::new(),
-            capacity,
+            capacity_bytes,
+            hits: 0,
+            misses: 0,
         }
     }
 
-    fn evict_if_necessary(&mut self) {
-        while self.cache.len() > self.capacity {
-            if let Some(victim) = self.lru.pop_front() {
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    fn resident_bytes(&self) -> usize {
+        self.cache.len() * PAGE_SIZE
+    }
+
+    // Evicts until there is room for one more page. Called *before* a new
+    // page is inserted so the page being faulted in is never itself a
+    // candidate victim (it would still be "clean" at that point).
+    fn evict_if_necessary(&mut self) -> std::io::Result<()> {
+        while self.resident_bytes() + PAGE_SIZE > self.capacity_bytes {
+            // One pass over the current LRU order looking for a clean victim.
+            let scan_len = self.lru.len();
+            let mut evicted_clean = false;
+            for _ in 0..scan_len {
+                let victim = match self.lru.pop_front() {
+                    Some(v) => v,
+                    None => break,
+                };
                 if self.dirty_flag.get(&victim).copied().unwrap_or(false) {
                     self.lru.push_back(victim);
                     continue;
                 }
                 self.cache.remove(&victim);
                 self.dirty_flag.remove(&victim);
-            } else {
+                evicted_clean = true;
                 break;
             }
+
+            if evicted_clean {
+                continue;
+            }
+
+            // Every resident page is dirty: write the oldest one back
+            // through the Pager instead of rotating it forever.
+            match self.lru.pop_front() {
+                Some(victim) => {
+                    if let Some(page) = self.cache.get(&victim) {
+                        self.pager.write_page(victim, &page.borrow())?;
+                    }
+                    self.cache.remove(&victim);
+                    self.dirty_flag.remove(&victim);
+                }
+                None => break,
+            }
         }
+        Ok(())
     }
 
     pub fn get_page(&mut self, page_id: usize) -> std::io::Result<PageData> {
         if let Some(page) = self.cache.get(&page_id) {
+            self.hits += 1;
             if let Some(pos) = self.lru.iter().position(|&id| id == page_id) {
                 self.lru.remove(pos);
             }
@@ -47,14 +104,24 @@ impl PageCache {
             return Ok(page.clone());
         }
 
+        self.misses += 1;
+        self.evict_if_necessary()?;
         let page_data = self.pager.read_page(page_id)?;
         let page_rc = Rc::new(RefCell::new(page_data));
         self.cache.insert(page_id, page_rc.clone());
         self.lru.push_back(page_id);
-        self.evict_if_necessary();
         return Ok(page_rc);
     }
 
+    /// Allocates a fresh page through `allocator` and brings it into the
+    /// cache, marked dirty, instead of the caller inventing a `page_id`.
+    pub fn new_page(&mut self, allocator: &mut Allocator) -> std::io::Result<(usize, PageData)> {
+        let page_id = allocator.allocate();
+        let page = self.get_page(page_id)?;
+        self.mark_dirty(page_id);
+        Ok((page_id, page))
+    }
+
     pub fn mark_dirty(&mut self, page_id: usize) {
         self.dirty_flag.insert(page_id, true);
     }
@@ -80,8 +147,8 @@ mod tests {
 
     fn setup_cache() -> (PageCache, NamedTempFile) {
         let temp = tempfile::NamedTempFile::new().unwrap();
-        let pager = Pager::open(temp.path()).unwrap();
-        let cache = PageCache::new(pager, 3); // 減少容量便於測 eviction
+        let pager = Arc::new(Pager::open(temp.path()).unwrap());
+        let cache = PageCache::new(pager, 3 * PAGE_SIZE); // 減少容量便於測 eviction
         (cache, temp)
     }
 
@@ -140,45 +207,63 @@ mod tests {
         assert!(cache.cache.get(&1).is_some());
     }
 
+    #[test]
+    fn test_new_page_allocates_through_allocator() {
+        // Cache and allocator share one Pager/file, so the id `new_page`
+        // hands out must be readable/writable through that exact file,
+        // not just through the in-memory cache entry `new_page` returns.
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let pager = Arc::new(Pager::open(temp.path()).unwrap());
+        let mut cache = PageCache::new(pager.clone(), 3 * PAGE_SIZE);
+        let mut alloc = crate::allocator::Allocator::open(pager).unwrap();
+
+        let (page_id, page) = cache.new_page(&mut alloc).unwrap();
+        assert_eq!(page_id, 2);
+        assert!(page.borrow().iter().all(|&b| b == 0));
+
+        page.borrow_mut()[..4].copy_from_slice(&[7, 7, 7, 7]);
+        cache.flush().unwrap();
+
+        let other_pager = Pager::open(temp.path()).unwrap();
+        let page_data = other_pager.read_page(page_id).unwrap();
+        assert_eq!(&page_data[..4], &[7, 7, 7, 7]);
+    }
+
     #[test]
     fn test_cache_hit_miss_statistics() {
-        struct StatsPageCache {
-            cache: PageCache,
-            hits: usize,
-            misses: usize,
-        }
+        let (mut cache, _) = setup_cache();
 
-        impl StatsPageCache {
-            fn new(pager: Pager, capacity: usize) -> Self {
-                Self {
-                    cache: PageCache::new(pager, capacity),
-                    hits: 0,
-                    misses: 0,
-                }
-            }
+        cache.get_page(1).unwrap(); // miss
+        cache.get_page(2).unwrap(); // miss
+        cache.get_page(1).unwrap(); // hit
+        cache.get_page(3).unwrap(); // miss
+        cache.get_page(2).unwrap(); // hit
 
-            fn get_page(&mut self, page_id: usize) -> std::io::Result<PageData> {
-                if self.cache.cache.contains_key(&page_id) {
-                    self.hits += 1;
-                } else {
-                    self.misses += 1;
-                }
-                self.cache.get_page(page_id)
-            }
-        }
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(cache.misses(), 3);
+    }
 
-        let temp = NamedTempFile::new().unwrap();
-        let pager = Pager::open(temp.path()).unwrap();
-        let mut stats_cache = StatsPageCache::new(pager, 3);
+    #[test]
+    fn test_eviction_writes_back_dirty_page_when_all_resident_are_dirty() {
+        let (mut cache, path) = setup_cache();
+
+        let page1 = cache.get_page(1).unwrap();
+        page1.borrow_mut()[..4].copy_from_slice(&[1, 1, 1, 1]);
+        cache.mark_dirty(1);
+        cache.get_page(2).unwrap();
+        cache.mark_dirty(2);
+        cache.get_page(3).unwrap();
+        cache.mark_dirty(3);
+
+        // Capacity is 3 pages and every resident page is dirty, so this
+        // must write page 1 back instead of rotating it forever.
+        cache.get_page(4).unwrap();
 
-        stats_cache.get_page(1).unwrap(); // miss
-        stats_cache.get_page(2).unwrap(); // miss
-        stats_cache.get_page(1).unwrap(); // hit
-        stats_cache.get_page(3).unwrap(); // miss
-        stats_cache.get_page(2).unwrap(); // hit
+        assert!(cache.cache.get(&1).is_none());
 
-        assert_eq!(stats_cache.hits, 2);
-        assert_eq!(stats_cache.misses, 3);
+        let pager = Pager::open(path).unwrap();
+        let page_data = pager.read_page(1).unwrap();
+        assert_eq!(&page_data[..4], &[1, 1, 1, 1]);
     }
 }
 